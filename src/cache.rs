@@ -1,12 +1,31 @@
+use dashmap::DashMap;
 use sha2::{Digest, Sha256};
-use std::time::Instant;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::time::interval;
+use crate::metrics::CACHE_SIZE;
 use crate::models::GenerateRequest;
 
-// Cache entry with timestamp
+// Cache entry with timestamp, size and last-access bookkeeping for LRU eviction
 #[derive(Clone)]
 pub struct CacheEntry {
     pub response: String,
     pub created_at: Instant,
+    pub last_accessed: Instant,
+    pub size_bytes: usize,
+}
+
+impl CacheEntry {
+    fn new(response: String, created_at: Instant) -> Self {
+        let size_bytes = response.len();
+        Self {
+            response,
+            created_at,
+            last_accessed: Instant::now(),
+            size_bytes,
+        }
+    }
 }
 
 // Create a cache key (hash of model + prompt)
@@ -15,4 +34,198 @@ pub fn make_cache_key(req: &GenerateRequest) -> String {
     hasher.update(&req.model);
     hasher.update(&req.prompt);
     format!("{:x}", hasher.finalize())
-}
\ No newline at end of file
+}
+
+// Response cache bounded by both entry count and approximate byte size, with
+// LRU eviction layered on top of TTL expiry so long-running deployments don't
+// grow without limit (only TTL staleness used to be checked, and expired
+// entries were never actually removed).
+pub struct BoundedCache {
+    entries: DashMap<String, CacheEntry>,
+    ttl: Duration,
+    max_entries: usize,
+    max_bytes: usize,
+    total_bytes: AtomicUsize,
+}
+
+impl BoundedCache {
+    pub fn new(ttl: Duration, max_entries: usize, max_bytes: usize) -> Self {
+        Self {
+            entries: DashMap::new(),
+            ttl,
+            max_entries,
+            max_bytes,
+            total_bytes: AtomicUsize::new(0),
+        }
+    }
+
+    // Look up a key, returning the cached response if present and not expired.
+    // Bumps last-accessed so the entry counts as recently used for LRU eviction.
+    pub fn get(&self, key: &str) -> Option<String> {
+        let mut entry = self.entries.get_mut(key)?;
+        if entry.created_at.elapsed() >= self.ttl {
+            return None;
+        }
+        entry.last_accessed = Instant::now();
+        Some(entry.response.clone())
+    }
+
+    // Insert a response, evicting least-recently-used entries until both the
+    // entry-count and byte-size limits are satisfied.
+    pub fn insert(&self, key: String, response: String) {
+        self.insert_entry(key, CacheEntry::new(response, Instant::now()));
+    }
+
+    // Insert an entry gossiped from a peer instance, honoring the TTL it had
+    // left there rather than resetting the full TTL locally. No-op once the
+    // reported remaining TTL has already elapsed.
+    pub fn insert_gossiped(&self, key: String, response: String, ttl_remaining: Duration) {
+        if ttl_remaining.is_zero() {
+            return;
+        }
+        let age = self.ttl.saturating_sub(ttl_remaining);
+        let created_at = Instant::now().checked_sub(age).unwrap_or_else(Instant::now);
+        self.insert_entry(key, CacheEntry::new(response, created_at));
+    }
+
+    fn insert_entry(&self, key: String, entry: CacheEntry) {
+        let size = entry.size_bytes;
+
+        if let Some(old) = self.entries.insert(key, entry) {
+            self.total_bytes.fetch_sub(old.size_bytes, Ordering::Relaxed);
+        }
+        self.total_bytes.fetch_add(size, Ordering::Relaxed);
+
+        self.evict_if_needed();
+        CACHE_SIZE.set(self.entries.len() as f64);
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    fn evict_if_needed(&self) {
+        while self.entries.len() > self.max_entries
+            || self.total_bytes.load(Ordering::Relaxed) > self.max_bytes
+        {
+            let Some(lru_key) = self
+                .entries
+                .iter()
+                .min_by_key(|e| e.last_accessed)
+                .map(|e| e.key().clone())
+            else {
+                break;
+            };
+            self.remove(&lru_key);
+        }
+    }
+
+    // Drop TTL-expired entries; called periodically by `cache_sweeper`.
+    pub fn sweep_expired(&self) {
+        let expired: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|e| e.created_at.elapsed() >= self.ttl)
+            .map(|e| e.key().clone())
+            .collect();
+
+        for key in expired {
+            self.remove(&key);
+        }
+        CACHE_SIZE.set(self.entries.len() as f64);
+    }
+
+    fn remove(&self, key: &str) {
+        if let Some((_, entry)) = self.entries.remove(key) {
+            self.total_bytes.fetch_sub(entry.size_bytes, Ordering::Relaxed);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+// Background sweeper that proactively drops TTL-expired entries so CACHE_SIZE
+// (and memory) reflect reality even between inserts/evictions.
+pub async fn cache_sweeper(cache: Arc<BoundedCache>, sweep_interval: Duration) {
+    let mut ticker = interval(sweep_interval);
+    loop {
+        ticker.tick().await;
+        cache.sweep_expired();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(max_entries: usize, max_bytes: usize) -> BoundedCache {
+        BoundedCache::new(Duration::from_secs(60), max_entries, max_bytes)
+    }
+
+    #[test]
+    fn evicts_oldest_accessed_entry_once_entry_count_exceeds_the_cap() {
+        let c = cache(2, usize::MAX);
+
+        c.insert("a".to_string(), "1".to_string());
+        std::thread::sleep(Duration::from_millis(5));
+        c.insert("b".to_string(), "2".to_string());
+        std::thread::sleep(Duration::from_millis(5));
+        c.insert("c".to_string(), "3".to_string());
+
+        assert_eq!(c.len(), 2);
+        assert!(c.get("a").is_none(), "oldest entry should have been evicted");
+        assert!(c.get("b").is_some());
+        assert!(c.get("c").is_some());
+    }
+
+    #[test]
+    fn evicts_entries_once_total_bytes_exceeds_the_cap() {
+        // Each value is 4 bytes; cap at 10 bytes allows at most 2 entries.
+        let c = cache(usize::MAX, 10);
+
+        c.insert("a".to_string(), "aaaa".to_string());
+        std::thread::sleep(Duration::from_millis(5));
+        c.insert("b".to_string(), "bbbb".to_string());
+        std::thread::sleep(Duration::from_millis(5));
+        c.insert("c".to_string(), "cccc".to_string());
+
+        assert!(c.get("a").is_none(), "oldest entry should have been evicted to stay under the byte cap");
+        assert!(c.get("b").is_some());
+        assert!(c.get("c").is_some());
+        assert!(c.len() <= 2);
+    }
+
+    #[test]
+    fn get_refreshes_last_accessed_so_recently_read_entries_survive_eviction() {
+        let c = cache(2, usize::MAX);
+
+        c.insert("a".to_string(), "1".to_string());
+        std::thread::sleep(Duration::from_millis(5));
+        c.insert("b".to_string(), "2".to_string());
+
+        // Touch "a" so it becomes the most-recently-used entry, leaving "b"
+        // as the LRU candidate even though it was inserted after "a".
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(c.get("a").is_some());
+
+        std::thread::sleep(Duration::from_millis(5));
+        c.insert("c".to_string(), "3".to_string());
+
+        assert!(c.get("a").is_some(), "recently accessed entry should survive eviction");
+        assert!(c.get("b").is_none(), "stale entry should be evicted instead");
+    }
+
+    #[test]
+    fn insert_at_exactly_the_cap_does_not_evict() {
+        let c = cache(2, usize::MAX);
+
+        c.insert("a".to_string(), "1".to_string());
+        c.insert("b".to_string(), "2".to_string());
+
+        assert_eq!(c.len(), 2);
+        assert!(c.get("a").is_some());
+        assert!(c.get("b").is_some());
+    }
+}
@@ -18,6 +18,14 @@ pub struct Args {
     #[arg(short, long, default_value_t = 30)]
     pub cache_ttl: u64,
 
+    // Maximum number of entries kept in the response cache before LRU eviction kicks in
+    #[arg(long, default_value_t = 10_000)]
+    pub cache_max_entries: usize,
+
+    // Maximum approximate total size (bytes) of the response cache before LRU eviction kicks in
+    #[arg(long, default_value_t = 100_000_000)]
+    pub cache_max_bytes: usize,
+
     // Rate limit max requests per window
     #[arg(long, default_value_t = 10)]
     pub rate_limit: u32,
@@ -28,5 +36,36 @@ pub struct Args {
 
     // Health check interval
     #[arg(long, default_value_t = 30)]
-    pub health_interval: u64
+    pub health_interval: u64,
+
+    // Peak-EWMA latency decay constant (seconds) used for backend selection
+    #[arg(long, default_value_t = 10)]
+    pub ewma_tau: u64,
+
+    // Maximum number of backends to try for a single request before giving up
+    #[arg(long, default_value_t = 2)]
+    pub max_retries: usize,
+
+    // Trust X-Forwarded-For / X-Real-IP headers for the rate-limit client key
+    // (only enable this when the gateway sits behind a proxy you control)
+    #[arg(long)]
+    pub trust_forwarded_for: bool,
+
+    // Maximum accepted size (bytes) of an incoming /api/generate request body
+    #[arg(long, default_value_t = 10_000_000)]
+    pub max_request_bytes: usize,
+
+    // Maximum accepted size (bytes) of a backend's /api/generate response body
+    #[arg(long, default_value_t = 50_000_000)]
+    pub max_response_bytes: usize,
+
+    // UDP address to bind for gossiping cache entries to peer gateway
+    // instances, e.g. "0.0.0.0:9999". Gossip is disabled unless this is set.
+    #[arg(long)]
+    pub gossip_bind: Option<String>,
+
+    // Comma-separated UDP addresses of peer gateway instances to gossip
+    // cache entries to, e.g. "10.0.0.2:9999,10.0.0.3:9999"
+    #[arg(long)]
+    pub gossip_peers: Option<String>,
 }
\ No newline at end of file
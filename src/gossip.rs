@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use crate::cache::BoundedCache;
+
+// Wire format for a gossiped cache entry. `make_cache_key`'s SHA-256 hash is
+// globally consistent, so any instance's key matches any other's for the
+// same model+prompt.
+#[derive(Serialize, Deserialize)]
+struct GossipMessage {
+    cache_key: String,
+    response: String,
+    ttl_remaining_secs: u64,
+}
+
+// Broadcasts newly-inserted cache entries to peer gateway instances so a
+// horizontally-scaled fleet can share cache hits instead of each instance
+// keeping an independent cache.
+pub struct GossipHandle {
+    socket: Arc<UdpSocket>,
+    peers: Vec<SocketAddr>,
+}
+
+impl GossipHandle {
+    pub fn new(socket: Arc<UdpSocket>, peers: Vec<SocketAddr>) -> Self {
+        Self { socket, peers }
+    }
+
+    // Send a just-inserted entry to every configured peer. Entries received
+    // via gossip never flow back through this (see `gossip_listener`), so a
+    // message can only ever hop once and can't cause rebroadcast storms.
+    pub async fn broadcast_insert(&self, cache_key: &str, response: &str, ttl_remaining: Duration) {
+        let message = GossipMessage {
+            cache_key: cache_key.to_string(),
+            response: response.to_string(),
+            ttl_remaining_secs: ttl_remaining.as_secs(),
+        };
+        let Ok(payload) = serde_json::to_vec(&message) else {
+            return;
+        };
+        for peer in &self.peers {
+            let _ = self.socket.send_to(&payload, peer).await;
+        }
+    }
+}
+
+// Listens for cache entries gossiped by peers and applies them to the local
+// cache, respecting the remaining TTL the peer reported. Only messages from
+// configured `gossip_peers` are applied — this is a trust boundary, not just
+// a parsing step, since anything else reaching `--gossip-bind` could inject
+// arbitrary cache entries for future clients to be served.
+pub async fn gossip_listener(socket: Arc<UdpSocket>, cache: Arc<BoundedCache>, peers: Vec<SocketAddr>) {
+    let mut buf = vec![0u8; 65536];
+    println!("Gossip listener started");
+
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf).await {
+            Ok(v) => v,
+            Err(e) => {
+                println!("[Gossip] Receive error: {}", e);
+                continue;
+            }
+        };
+
+        if !peers.contains(&from) {
+            println!("[Gossip] Dropping message from untrusted peer {}", from);
+            continue;
+        }
+
+        let Ok(message) = serde_json::from_slice::<GossipMessage>(&buf[..len]) else {
+            continue;
+        };
+
+        let ttl_remaining = Duration::from_secs(message.ttl_remaining_secs).min(cache.ttl());
+        cache.insert_gossiped(message.cache_key, message.response, ttl_remaining);
+    }
+}
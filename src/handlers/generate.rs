@@ -1,7 +1,20 @@
-use axum::{Json, extract::State};
+use axum::{
+    Json,
+    body::Body,
+    extract::{ConnectInfo, State},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use futures_util::Stream;
+use std::net::SocketAddr;
+use std::pin::Pin;
 use std::sync::Arc;
-use std::time::Instant;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 use tokio::sync::oneshot;
+use crate::load_balancer::Backend;
+use crate::rate_limit::resolve_client_key;
 use crate::state::AppState;
 use crate::models::{GenerateRequest, GenerateResponse, BatchedRequest};
 use crate::metrics::{REQUEST_TOTAL, REQUEST_LATENCY};
@@ -34,14 +47,21 @@ fn check_rate_limit(state: &AppState, ip: &str) -> bool {
 
 pub async fn generate_handler(
     State(state): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(payload): Json<GenerateRequest>,
-) -> Result<Json<GenerateResponse>, String> {
+) -> Result<Response, String> {
     REQUEST_TOTAL.inc();
 
-    if !check_rate_limit(&state, "global") {
+    let client_key = resolve_client_key(state.trust_forwarded_for, &headers, peer);
+    if !check_rate_limit(&state, &client_key) {
         return Err("Rate limit exceeded. Try again later.".to_string());
     }
 
+    if payload.stream {
+        return stream_generate(state, payload).await;
+    }
+
     let start_time = Instant::now();
 
     let (response_tx, response_rx) = oneshot::channel();
@@ -59,5 +79,92 @@ pub async fn generate_handler(
 
     REQUEST_LATENCY.observe(start_time.elapsed().as_secs_f64());
 
-    result.map(Json)
-}
\ No newline at end of file
+    result.map(|body| Json(body).into_response())
+}
+
+// Proxies `stream: true` requests straight through to the backend, chunk by
+// chunk, instead of buffering the full response into a `GenerateResponse`
+// (which makes the buffered path unusable for token-by-token UIs). Bypasses
+// the batch worker and cache entirely since there's nothing to coalesce or
+// cache a partial stream onto.
+async fn stream_generate(
+    state: Arc<AppState>,
+    payload: GenerateRequest,
+) -> Result<Response, String> {
+    let start_time = Instant::now();
+    let tau = state.load_balancer.tau();
+
+    let backend = state
+        .load_balancer
+        .get_backend()
+        .ok_or_else(|| "No healthy backends available".to_string())?;
+
+    // Held for the whole streamed response, not just the initial request, so
+    // the Peak-EWMA cost function sees a backend serving a long-running
+    // stream as busy rather than idle.
+    backend.begin_request();
+
+    let upstream = state
+        .client
+        .post(format!("{}/api/generate", backend.url))
+        .json(&payload)
+        .send()
+        .await
+        .map_err(|e| {
+            backend.record_failure();
+            backend.end_request();
+            format!("Request failed: {}", e)
+        })?;
+
+    let status = upstream.status();
+    if status.is_server_error() {
+        backend.record_failure();
+    }
+
+    let stream = StreamingProxyBody {
+        inner: upstream.bytes_stream(),
+        backend,
+        start_time,
+        tau,
+    };
+
+    let mut response = Response::new(Body::from_stream(stream));
+    *response.status_mut() = status;
+    Ok(response)
+}
+
+// Wraps the upstream byte stream so we can penalize the backend's health
+// score on a mid-stream error and record `REQUEST_LATENCY`/`end_request`
+// once the stream is finished or dropped (on Drop, since the handler returns
+// before streaming finishes).
+struct StreamingProxyBody<S> {
+    inner: S,
+    backend: Arc<Backend>,
+    start_time: Instant,
+    tau: Duration,
+}
+
+impl<S> Stream for StreamingProxyBody<S>
+where
+    S: Stream<Item = reqwest::Result<Bytes>> + Unpin,
+{
+    type Item = reqwest::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let item = Pin::new(&mut this.inner).poll_next(cx);
+        if let Poll::Ready(Some(Err(_))) = &item {
+            this.backend.record_failure();
+        }
+        item
+    }
+}
+
+impl<S> Drop for StreamingProxyBody<S> {
+    fn drop(&mut self) {
+        let elapsed = self.start_time.elapsed();
+        REQUEST_LATENCY.observe(elapsed.as_secs_f64());
+        self.backend.record_latency(elapsed, self.tau);
+        self.backend.end_request();
+    }
+}
@@ -0,0 +1,9 @@
+use axum::{Json, response::IntoResponse};
+
+// Health check endpoint
+pub async fn health_handler() -> impl IntoResponse {
+    Json(serde_json::json!({
+        "status": "healthy",
+        "timestamp": chrono::Utc::now().to_rfc3339()
+    }))
+}
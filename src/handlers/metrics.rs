@@ -0,0 +1,11 @@
+use axum::response::IntoResponse;
+use prometheus::{Encoder, TextEncoder};
+
+// Prometheus scrape endpoint
+pub async fn metrics_handler() -> impl IntoResponse {
+    let encoder = TextEncoder::new();
+    let metric_families = prometheus::gather();
+    let mut buffer = Vec::new();
+    encoder.encode(&metric_families, &mut buffer).unwrap();
+    String::from_utf8(buffer).unwrap()
+}
@@ -1,30 +1,97 @@
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use tokio::time::{Duration, interval};
-
-
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicI32, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::time::interval;
+
+// Health score bounds. A backend starts fully healthy; a single failed
+// request only costs part of the budget so one transient error doesn't pull
+// it out of rotation, but a string of failures does.
+const HEALTHY_SCORE: i32 = 100;
+const FAILURE_PENALTY: i32 = 40;
+const RECOVERY_STEP: i32 = 15;
+const HEALTHY_THRESHOLD: i32 = 0;
+const FLOOR_SCORE: i32 = -100;
 
 // Single Backend server
 
 pub struct Backend {
     pub url: String,
-    pub helathy: AtomicBool, // is it owrking..?
+    health_score: AtomicI32,        // decaying health score, see HEALTHY_THRESHOLD
+    latency_estimate_ns: AtomicU64, // Peak-EWMA round-trip latency estimate
+    last_update: Mutex<Instant>,    // last time the latency estimate was decayed/updated
+    in_flight: AtomicUsize,         // requests currently dispatched to this backend
 }
 
 impl Backend {
     pub fn new(url: String) -> Self {
         Self {
             url,
-            helathy: AtomicBool::new(true),
+            health_score: AtomicI32::new(HEALTHY_SCORE),
+            latency_estimate_ns: AtomicU64::new(0),
+            last_update: Mutex::new(Instant::now()),
+            in_flight: AtomicUsize::new(0),
         }
     }
 
     pub fn is_healthy(&self) -> bool {
-        self.helathy.load(Ordering::Relaxed)
+        self.health_score.load(Ordering::Relaxed) > HEALTHY_THRESHOLD
+    }
+
+    // Penalize a backend after a connection error or 5xx response. Clamped
+    // to a floor so an extended outage doesn't require an unbounded number
+    // of successful health checks to recover from once the backend returns.
+    pub fn record_failure(&self) {
+        self.health_score
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |score| {
+                Some((score - FAILURE_PENALTY).max(FLOOR_SCORE))
+            })
+            .ok();
+    }
+
+    // Nudge a backend's score back toward fully healthy after a good response.
+    pub fn record_recovery(&self) {
+        self.health_score
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |score| {
+                Some((score + RECOVERY_STEP).min(HEALTHY_SCORE))
+            })
+            .ok();
+    }
+
+    pub fn begin_request(&self) {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn end_request(&self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
     }
 
-    pub fn set_healthy(&self, healthy: bool) {
-        self.helathy.store(healthy, Ordering::Relaxed);
+    // Peak-EWMA update: decay the estimate toward `rtt`, but jump straight to
+    // `rtt` whenever it's slower than the current estimate so a single slow
+    // response is reflected immediately (the "peak" in peak-EWMA).
+    pub fn record_latency(&self, rtt: Duration, tau: Duration) {
+        let rtt_ns = rtt.as_nanos() as u64;
+        let mut last_update = self.last_update.lock().unwrap();
+        let now = Instant::now();
+        let dt = now.saturating_duration_since(*last_update);
+        *last_update = now;
+
+        let estimate = self.latency_estimate_ns.load(Ordering::Relaxed);
+        let new_estimate = if estimate == 0 || rtt_ns > estimate {
+            rtt_ns
+        } else {
+            let w = (-dt.as_secs_f64() / tau.as_secs_f64()).exp();
+            (estimate as f64 * w + rtt_ns as f64 * (1.0 - w)) as u64
+        };
+        self.latency_estimate_ns.store(new_estimate, Ordering::Relaxed);
+    }
+
+    // Cost used to pick a backend: latency estimate weighted by how many
+    // requests are already in flight to it, so busy backends get skipped
+    // even if their last measured latency was good.
+    fn cost(&self) -> f64 {
+        let estimate = self.latency_estimate_ns.load(Ordering::Relaxed) as f64;
+        let in_flight = self.in_flight.load(Ordering::Relaxed) as f64;
+        estimate * (in_flight + 1.0)
     }
 }
 
@@ -33,13 +100,18 @@ impl Backend {
 pub struct LoadBalancer {
     pub backends: Vec<Arc<Backend>>,
     current: AtomicUsize,
+    tau: Duration, // Peak-EWMA decay constant
 }
 
 impl LoadBalancer {
     // Create from comma-seprated urls "localhst::11434, localhost::11435"
     pub fn new(backends_str: &str) -> Self {
+        Self::with_tau(backends_str, Duration::from_secs(10))
+    }
+
+    pub fn with_tau(backends_str: &str, tau: Duration) -> Self {
         let backends: Vec<Arc<Backend>> = backends_str
-            .split(',') 
+            .split(',')
             .map(|s| s.trim())// remove spaces
             .filter(|s| !s.is_empty())// remove empty strings
             .map(|url| {
@@ -67,11 +139,45 @@ impl LoadBalancer {
         Self {
             backends,
             current: AtomicUsize::new(0),
+            tau,
         }
     }
 
-    // Get next healthy backend (round-robin)
+    pub fn tau(&self) -> Duration {
+        self.tau
+    }
+
+    // Pick the healthy backend with the lowest Peak-EWMA cost (latency
+    // estimate weighted by in-flight requests). Falls back to round-robin
+    // while every estimate is still zero (cold start, no latency data yet).
     pub fn get_backend(&self) -> Option<Arc<Backend>> {
+        self.select(&[])
+    }
+
+    // Same as `get_backend`, but skips any backend already in `exclude` -
+    // used by the retry loop to avoid picking the backend that just failed.
+    pub fn get_backend_excluding(&self, exclude: &[Arc<Backend>]) -> Option<Arc<Backend>> {
+        self.select(exclude)
+    }
+
+    fn select(&self, exclude: &[Arc<Backend>]) -> Option<Arc<Backend>> {
+        let candidates = || {
+            self.backends
+                .iter()
+                .filter(|b| b.is_healthy() && !exclude.iter().any(|e| Arc::ptr_eq(e, b)))
+        };
+
+        let cold_start = candidates().all(|b| b.latency_estimate_ns.load(Ordering::Relaxed) == 0);
+        if cold_start {
+            return self.round_robin(exclude);
+        }
+
+        candidates()
+            .min_by(|a, b| a.cost().partial_cmp(&b.cost()).unwrap())
+            .map(Arc::clone)
+    }
+
+    fn round_robin(&self, exclude: &[Arc<Backend>]) -> Option<Arc<Backend>> {
         let len = self.backends.len();
         let start = self.current.fetch_add(1, Ordering::Relaxed) % len;
 
@@ -79,7 +185,7 @@ impl LoadBalancer {
             let idx = (start + i) % len;
             let backend = &self.backends[idx];
 
-            if backend.is_healthy() {
+            if backend.is_healthy() && !exclude.iter().any(|e| Arc::ptr_eq(e, backend)) {
                 return Some(Arc::clone(backend));
             }
         }
@@ -112,13 +218,13 @@ pub async fn health_checker(
 
             let was_healthy = backend.is_healthy();
 
-            let is_healthy = match client.get(&url).timeout(Duration::from_secs(5)).send().await {
-                Ok(res) => res.status().is_success(),
-                Err(_) => false,
-            };
-            backend.set_healthy(is_healthy);
+            match client.get(&url).timeout(Duration::from_secs(5)).send().await {
+                Ok(res) if res.status().is_success() => backend.record_recovery(),
+                _ => backend.record_failure(),
+            }
 
             // Log status changes
+            let is_healthy = backend.is_healthy();
             if was_healthy != is_healthy {
                 if is_healthy {
                     println!("Backend {} is now Healthy", backend.url);
@@ -129,20 +235,3 @@ pub async fn health_checker(
         }
     }
 }
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
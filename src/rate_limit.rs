@@ -1,7 +1,103 @@
-use std::time::Instant;
+use axum::http::HeaderMap;
+use dashmap::DashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::interval;
 
 // Rate limit entry - tracks requests per IP/key
 pub struct RateLimitEntry {
     pub count: u32,
     pub window_start: Instant,
-}
\ No newline at end of file
+}
+
+// Resolve the key used for per-client rate limiting. When `trust_forwarded_for`
+// is set (the gateway sits behind a proxy we control) the leftmost address in
+// `X-Forwarded-For`, or `X-Real-IP`, is used; otherwise we fall back to the
+// TCP peer address so a client can't just forge the header to dodge the limit.
+pub fn resolve_client_key(trust_forwarded_for: bool, headers: &HeaderMap, peer: SocketAddr) -> String {
+    if trust_forwarded_for {
+        let forwarded_for = headers
+            .get("x-forwarded-for")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(',').next())
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty());
+
+        if let Some(ip) = forwarded_for {
+            return ip.to_string();
+        }
+
+        let real_ip = headers
+            .get("x-real-ip")
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty());
+
+        if let Some(ip) = real_ip {
+            return ip.to_string();
+        }
+    }
+
+    peer.ip().to_string()
+}
+
+// Background sweeper that evicts rate-limit entries whose window closed a
+// while ago, so the map doesn't grow by one entry per unique client forever.
+pub async fn rate_limiter_sweeper(
+    rate_limiter: Arc<DashMap<String, RateLimitEntry>>,
+    rate_window: Duration,
+    sweep_interval: Duration,
+) {
+    let mut ticker = interval(sweep_interval);
+    loop {
+        ticker.tick().await;
+        rate_limiter.retain(|_, entry| entry.window_start.elapsed() <= rate_window * 2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer() -> SocketAddr {
+        "203.0.113.9:443".parse().unwrap()
+    }
+
+    fn headers_with(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn untrusted_deployment_ignores_forwarded_headers_and_uses_peer_addr() {
+        let headers = headers_with(&[("x-forwarded-for", "1.2.3.4"), ("x-real-ip", "5.6.7.8")]);
+        assert_eq!(resolve_client_key(false, &headers, peer()), peer().ip().to_string());
+    }
+
+    #[test]
+    fn trusted_deployment_prefers_leftmost_x_forwarded_for_entry() {
+        let headers = headers_with(&[("x-forwarded-for", "1.2.3.4, 10.0.0.1, 10.0.0.2")]);
+        assert_eq!(resolve_client_key(true, &headers, peer()), "1.2.3.4");
+    }
+
+    #[test]
+    fn trusted_deployment_falls_back_to_x_real_ip_when_forwarded_for_is_absent() {
+        let headers = headers_with(&[("x-real-ip", "5.6.7.8")]);
+        assert_eq!(resolve_client_key(true, &headers, peer()), "5.6.7.8");
+    }
+
+    #[test]
+    fn trusted_deployment_falls_back_to_peer_addr_when_no_headers_present() {
+        let headers = HeaderMap::new();
+        assert_eq!(resolve_client_key(true, &headers, peer()), peer().ip().to_string());
+    }
+
+    #[test]
+    fn trusted_deployment_falls_back_to_peer_addr_when_forwarded_for_is_blank() {
+        let headers = headers_with(&[("x-forwarded-for", "   ")]);
+        assert_eq!(resolve_client_key(true, &headers, peer()), peer().ip().to_string());
+    }
+}
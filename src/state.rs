@@ -1,20 +1,22 @@
 use dashmap::DashMap;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::mpsc;
-use crate::cache::CacheEntry;
+use tokio::sync::{mpsc, oneshot};
+use crate::cache::BoundedCache;
 use crate::rate_limit::RateLimitEntry;
-use crate::models::BatchedRequest;
+use crate::models::{BatchedRequest, GenerateResponse};
 use crate::load_balancer::LoadBalancer;
 // app's shared state
 
 pub struct AppState {
     pub client: reqwest::Client,
-    pub cache: DashMap<String, CacheEntry>, // String -> CacheEntry
-    pub ttl: Duration,                      // how long cache will be valid
+    pub cache: Arc<BoundedCache>, // shared with the worker and the sweeper
     pub load_balancer: Arc<LoadBalancer>,
-    pub rate_limiter: DashMap<String, RateLimitEntry>,
+    pub rate_limiter: Arc<DashMap<String, RateLimitEntry>>, // shared with the sweeper
     pub rate_limit: u32,       // max request allowed
     pub rate_window: Duration, // Duration of rate limit
+    pub trust_forwarded_for: bool, // trust X-Forwarded-For / X-Real-IP for the rate-limit key
     pub batch_tx: mpsc::Sender<BatchedRequest>,
-}
\ No newline at end of file
+    // single-flight: requests already waiting on a backend call for a given cache key
+    pub in_flight: Arc<DashMap<String, Vec<oneshot::Sender<Result<GenerateResponse, String>>>>>,
+}
@@ -1,81 +1,191 @@
+use bytes::{Bytes, BytesMut};
 use dashmap::DashMap;
+use futures_util::StreamExt;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
-use tokio::sync::mpsc;
-use crate::cache::CacheEntry;
-use crate::load_balancer::{LoadBalancer};
+use std::time::Instant;
+use tokio::sync::{mpsc, oneshot};
+use crate::cache::BoundedCache;
+use crate::gossip::GossipHandle;
+use crate::load_balancer::{Backend, LoadBalancer};
 use crate::models::{BatchedRequest, GenerateResponse};
 use crate::cache::make_cache_key;
-use crate::metrics::{CACHE_HITS, CACHE_MISSES, CACHE_SIZE};
+use crate::metrics::{CACHE_HITS, CACHE_MISSES};
 
 
 pub async fn batch_worker(
     mut rx: mpsc::Receiver<BatchedRequest>,
     client: reqwest::Client,
     load_balancer: Arc<LoadBalancer>,
-    cache: DashMap<String, CacheEntry>,
-    ttl: Duration,
+    cache: Arc<BoundedCache>,
+    in_flight: Arc<DashMap<String, Vec<oneshot::Sender<Result<GenerateResponse, String>>>>>,
+    max_retries: usize,
+    max_response_bytes: usize,
+    gossip: Option<Arc<GossipHandle>>,
 ) {
-    println!("Batch worker started - processing requests sequentially");
+    println!("Batch worker started - processing requests with single-flight coalescing");
 
     // keep receiving the requests from queue
     while let Some(batched_req) = rx.recv().await {
         let cache_key = make_cache_key(&batched_req.request);
 
         // check cache first
-        if let Some(entry) = cache.get(&cache_key) {
-            if entry.created_at.elapsed() < ttl {
-                CACHE_HITS.inc();
-                println!("[Worker] Cache HIT");
-                if let Ok(response) = serde_json::from_str(&entry.response) {
-                    let _ = batched_req.response_tx.send(Ok(response));
-                    continue;
-                }
+        if let Some(raw) = cache.get(&cache_key) {
+            CACHE_HITS.inc();
+            println!("[Worker] Cache HIT");
+            if let Ok(response) = serde_json::from_str(&raw) {
+                let _ = batched_req.response_tx.send(Ok(response));
+                continue;
             }
         }
         CACHE_MISSES.inc();
 
+        // single-flight: if a call for this key is already in flight, just join its waiter list
+        let mut waiters = in_flight.entry(cache_key.clone()).or_insert_with(Vec::new);
+        waiters.push(batched_req.response_tx);
+        if waiters.len() > 1 {
+            println!("[Worker] Coalescing onto in-flight request");
+            continue;
+        }
+        drop(waiters);
+
         let backend = match load_balancer.get_backend() {
             Some(b) => b,
             None => {
-                let _ = batched_req.response_tx.send(Err("No Healthy backends available".to_string()));
+                if let Some((_, waiters)) = in_flight.remove(&cache_key) {
+                    for tx in waiters {
+                        let _ = tx.send(Err("No healthy backends available".to_string()));
+                    }
+                }
                 continue;
             }
         };
-        println!("[Worker] Using Backend: {}", backend.url);
 
-        // Call ollama
+        let client = client.clone();
+        let cache = Arc::clone(&cache);
+        let in_flight = Arc::clone(&in_flight);
+        let load_balancer = Arc::clone(&load_balancer);
+        let request = batched_req.request.clone();
+        let gossip = gossip.clone();
+
+        // run the backend call (and any retries) on its own task so other
+        // queued requests aren't blocked behind it and can coalesce onto it
+        tokio::spawn(async move {
+            let response = dispatch_with_retries(
+                &client,
+                &load_balancer,
+                &cache,
+                &cache_key,
+                backend,
+                &request,
+                max_retries,
+                max_response_bytes,
+                gossip.as_deref(),
+            )
+            .await;
+
+            // fan the result out to every request that coalesced onto this call
+            if let Some((_, waiters)) = in_flight.remove(&cache_key) {
+                for tx in waiters {
+                    let _ = tx.send(response.clone());
+                }
+            }
+        });
+    }
+}
+
+// Sends `request` to `backend`, retrying on the next healthy backend (up to
+// `max_retries` candidates total) on a connection error or 5xx response
+// instead of failing the request on the first bad backend.
+async fn dispatch_with_retries(
+    client: &reqwest::Client,
+    load_balancer: &Arc<LoadBalancer>,
+    cache: &Arc<BoundedCache>,
+    cache_key: &str,
+    mut backend: Arc<Backend>,
+    request: &crate::models::GenerateRequest,
+    max_retries: usize,
+    max_response_bytes: usize,
+    gossip: Option<&GossipHandle>,
+) -> Result<GenerateResponse, String> {
+    let tau = load_balancer.tau();
+    let mut tried = Vec::with_capacity(max_retries);
+    let mut last_error = "No healthy backends available".to_string();
+
+    for _ in 0..max_retries.max(1) {
+        println!("[Worker] Using Backend: {}", backend.url);
+        backend.begin_request();
+        let dispatched_at = Instant::now();
         let result = client
-        .post(format!("{}/api/generate", backend.url)) // use backend.url
-        .json(&batched_req.request)
-        .send()
-        .await;
+            .post(format!("{}/api/generate", backend.url))
+            .json(request)
+            .send()
+            .await;
 
-        let response = match result {
+        // Connection errors and 5xx responses are treated as backend health
+        // problems and retried on the next candidate; a parse error on an
+        // otherwise-successful response is not the backend's fault and is
+        // returned to the caller immediately. `end_request()` is deferred
+        // until the body is fully read (or the attempt otherwise fails) so
+        // the in-flight count reflects work the backend is still doing, not
+        // just time-to-first-byte.
+        match result {
+            Ok(res) if res.status().is_server_error() => {
+                last_error = format!("Backend returned {}", res.status());
+                backend.end_request();
+            }
             Ok(res) => {
-                match res.json::<GenerateResponse>().await {
-                    Ok(body) => {
-                        // saving to cache
-                        if let Ok(json) = serde_json::to_string(&body) {
-                            cache.insert(cache_key, CacheEntry {
-                                response: json,
-                                created_at: Instant::now(),
-                            });
-                            CACHE_SIZE.set(cache.len() as f64); 
+                let outcome = match read_response_capped(res, max_response_bytes).await {
+                    Ok(raw) => match serde_json::from_slice::<GenerateResponse>(&raw) {
+                        Ok(body) => {
+                            backend.record_recovery();
+                            backend.record_latency(dispatched_at.elapsed(), tau);
+                            if let Ok(json) = serde_json::to_string(&body) {
+                                cache.insert(cache_key.to_string(), json.clone());
+                                if let Some(gossip) = gossip {
+                                    gossip.broadcast_insert(cache_key, &json, cache.ttl()).await;
+                                }
+                            }
+                            Ok(body)
                         }
-                        Ok(body)
-                    }
-                    Err(e) => Err(format!("Parse Error: {}", e))
-                }
+                        Err(e) => Err(format!("Parse Error: {}", e)),
+                    },
+                    Err(e) => Err(e),
+                };
+                backend.end_request();
+                return outcome;
             }
-            // Marking backend as unhelathy on error
             Err(e) => {
-                backend.set_healthy(false);
-                println!("[Worker] Backend {} failed, marked unhealthy", backend.url);
-                Err(format!("Request failed: {}", e))
+                last_error = format!("Request failed: {}", e);
+                backend.end_request();
             }
-        };
-        // Send response back to handler
-        let _ = batched_req.response_tx.send(response);
+        }
+
+        backend.record_failure();
+        println!("[Worker] Backend {} failed ({}), trying next", backend.url, last_error);
+
+        tried.push(Arc::clone(&backend));
+        match load_balancer.get_backend_excluding(&tried) {
+            Some(next) => backend = next,
+            None => break,
+        }
     }
-}
\ No newline at end of file
+
+    Err(format!("All backends exhausted: {}", last_error))
+}
+
+// Streams the backend's response body, aborting once `max_bytes` is crossed
+// instead of buffering an unbounded body into memory via `res.json()`.
+async fn read_response_capped(res: reqwest::Response, max_bytes: usize) -> Result<Bytes, String> {
+    let mut stream = res.bytes_stream();
+    let mut buf = BytesMut::new();
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| format!("Stream error: {}", e))?;
+        if buf.len() + chunk.len() > max_bytes {
+            return Err(format!("Response exceeded {} byte limit", max_bytes));
+        }
+        buf.extend_from_slice(&chunk);
+    }
+
+    Ok(buf.freeze())
+}